@@ -0,0 +1,918 @@
+// Storage backends: where the server keeps its memories.
+//
+// The markdown file is the default, and the only one meant to be read and edited by hand.
+// The SQLite database keeps the same entries in a table, so adding or deleting one doesn't
+// go through the whole store, and the JSON Lines file holds one JSON object per memory, easy
+// to process with other programs. Backends cover what every storage has to offer; tools that
+// work on the markdown file directly are only available with it.
+use crate::memory::{
+    Category, Deletion, FileLock, MemoryEntry, Page, SaveResult, Selector, Update,
+    append_entries_to_file_within, clear_memories_from_file, count_memories_in_file,
+    delete_memory_from_file, find_duplicate, format_page, format_search_results, format_timestamp,
+    get_memories_from_file, parse_timestamp, purge_expired_from_file, read_entries_from_file,
+    replace_file, replace_memories_in_file, save_entry_to_file, unix_now, update_memory_in_file,
+};
+use rusqlite::{Connection, OptionalExtension, Row, Transaction};
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// What the server needs from wherever memories are stored
+pub trait MemoryBackend: Send + Sync {
+    // Save new memories after the existing ones
+    fn add(&self, entries: &[MemoryEntry]) -> anyhow::Result<()>;
+
+    // Save a new memory after the existing ones, unless one with the same content is stored
+    fn save(&self, entry: &MemoryEntry) -> anyhow::Result<SaveResult> {
+        let entries = self.list()?;
+        if let Some(position) = find_duplicate(&entries, &entry.content) {
+            return Ok(SaveResult {
+                was_duplicate: true,
+                index: position + 1,
+            });
+        }
+        self.add(std::slice::from_ref(entry))?;
+        Ok(SaveResult {
+            was_duplicate: false,
+            index: entries.len() + 1,
+        })
+    }
+
+    // Every memory, oldest first
+    fn list(&self) -> anyhow::Result<Vec<MemoryEntry>>;
+
+    // Delete the selected memory, indexes follow the order of `list`
+    fn delete(&self, selector: &Selector) -> anyhow::Result<Deletion>;
+
+    // Replace the content of the selected memory, keeping the rest and recording when it
+    // changed. Indexes follow the order of `list`.
+    fn update(&self, selector: &Selector, content: &str) -> anyhow::Result<Update>;
+
+    // Delete every memory, returning how many there were
+    fn clear(&self) -> anyhow::Result<usize>;
+
+    // Delete every memory and save `entries` instead, all or nothing. Returns how many
+    // memories there were.
+    fn replace(&self, entries: &[MemoryEntry]) -> anyhow::Result<usize>;
+
+    // Delete the memories that expired by `now` (Unix seconds), returning how many there were.
+    // They are already left out of everything else.
+    fn purge_expired(&self, now: i64) -> anyhow::Result<usize>;
+
+    // How many memories there are
+    fn count(&self) -> anyhow::Result<usize> {
+        Ok(self.list()?.len())
+    }
+
+    // A page of memories, numbered as `delete` expects them
+    fn get_memories(&self, page: Page) -> anyhow::Result<String> {
+        let entries = self.list()?;
+        if entries.is_empty() {
+            return Ok("No memories found yet.".to_string());
+        }
+        Ok(format_page(&entries, page))
+    }
+
+    // The memories containing the query, ignoring case, optionally with the matches wrapped
+    // in an opening and closing marker
+    fn search(&self, query: &str, highlight: Option<(&str, &str)>) -> anyhow::Result<String> {
+        Ok(format_search_results(self.list()?, query, highlight))
+    }
+}
+
+// The markdown memory file, `None` meaning `memories.md` in the current directory
+#[derive(Clone, Debug, Default)]
+pub struct MarkdownBackend {
+    file_path: Option<String>,
+    // Size in bytes the file may not grow past, unlimited when None
+    max_file_size: Option<u64>,
+}
+
+impl MarkdownBackend {
+    pub fn new(file_path: Option<&str>) -> Self {
+        MarkdownBackend {
+            file_path: file_path.map(str::to_string),
+            max_file_size: None,
+        }
+    }
+
+    pub fn with_max_file_size(mut self, max_file_size: Option<u64>) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+}
+
+impl MemoryBackend for MarkdownBackend {
+    fn add(&self, entries: &[MemoryEntry]) -> anyhow::Result<()> {
+        append_entries_to_file_within(entries, self.max_file_size, self.file_path.as_deref())
+    }
+
+    // Looks for a duplicate under the same lock as the write
+    fn save(&self, entry: &MemoryEntry) -> anyhow::Result<SaveResult> {
+        save_entry_to_file(entry, self.max_file_size, self.file_path.as_deref())
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<MemoryEntry>> {
+        read_entries_from_file(self.file_path.as_deref())
+    }
+
+    fn delete(&self, selector: &Selector) -> anyhow::Result<Deletion> {
+        delete_memory_from_file(selector, self.file_path.as_deref())
+    }
+
+    fn update(&self, selector: &Selector, content: &str) -> anyhow::Result<Update> {
+        update_memory_in_file(selector, content, self.file_path.as_deref())
+    }
+
+    fn clear(&self) -> anyhow::Result<usize> {
+        clear_memories_from_file(self.file_path.as_deref())
+    }
+
+    fn replace(&self, entries: &[MemoryEntry]) -> anyhow::Result<usize> {
+        replace_memories_in_file(entries, self.max_file_size, self.file_path.as_deref())
+    }
+
+    fn purge_expired(&self, now: i64) -> anyhow::Result<usize> {
+        purge_expired_from_file(now, self.file_path.as_deref())
+    }
+
+    fn count(&self) -> anyhow::Result<usize> {
+        count_memories_in_file(self.file_path.as_deref())
+    }
+
+    // Also warns about blocks of the file that could not be read
+    fn get_memories(&self, page: Page) -> anyhow::Result<String> {
+        get_memories_from_file(page, self.file_path.as_deref())
+    }
+}
+
+// Columns read back into a `MemoryEntry`, in the order `entry_from_row` expects
+const COLUMNS: &str =
+    "content, tags, created_at, source, importance, category, uid, expires_at, updated_at";
+
+// Rows still in use at ?1, the current time
+const LIVE: &str = "(expires_at IS NULL OR expires_at > ?1)";
+
+// A SQLite database holding one `memories` row per memory
+pub struct SqliteBackend {
+    // A connection can't be used from two threads at once
+    connection: Mutex<Connection>,
+}
+
+impl SqliteBackend {
+    // Open the database at `path`, creating it and the table when missing
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS memories (
+                id INTEGER PRIMARY KEY,
+                content TEXT NOT NULL,
+                tags TEXT NOT NULL DEFAULT '',
+                created_at INTEGER NOT NULL,
+                source TEXT,
+                importance INTEGER,
+                category TEXT,
+                uid TEXT,
+                expires_at INTEGER,
+                updated_at INTEGER
+            )",
+        )?;
+
+        // Databases created before memories had ids, could expire or be updated get the
+        // columns, their rows stay without an id, never expire and were never updated
+        for (column, column_type) in [
+            ("uid", "TEXT"),
+            ("expires_at", "INTEGER"),
+            ("updated_at", "INTEGER"),
+        ] {
+            let exists = connection
+                .prepare("SELECT name FROM pragma_table_info('memories') WHERE name = ?1")?
+                .exists([column])?;
+            if !exists {
+                connection.execute_batch(&format!(
+                    "ALTER TABLE memories ADD COLUMN {} {}",
+                    column, column_type
+                ))?;
+            }
+        }
+
+        Ok(SqliteBackend {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+// Helper function to insert entries as new rows, the caller commits the transaction
+fn insert_entries(transaction: &Transaction, entries: &[MemoryEntry]) -> anyhow::Result<()> {
+    for entry in entries {
+        let created_at = parse_timestamp(&entry.timestamp)
+            .ok_or_else(|| anyhow::anyhow!("Invalid timestamp {:?}", entry.timestamp))?;
+        let expires_at = match &entry.expires {
+            Some(expires) => Some(
+                parse_timestamp(expires)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid expiry {:?}", expires))?,
+            ),
+            None => None,
+        };
+        let updated_at = match &entry.updated {
+            Some(updated) => Some(
+                parse_timestamp(updated)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid update time {:?}", updated))?,
+            ),
+            None => None,
+        };
+        transaction.execute(
+            "INSERT INTO memories
+            (content, tags, created_at, source, importance, category, uid, expires_at, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            rusqlite::params![
+                entry.content,
+                entry.tags.join(", "),
+                created_at,
+                entry.source,
+                entry.importance,
+                entry.category.map(Category::name),
+                entry.id,
+                expires_at,
+                updated_at,
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+// Helper function to turn a row selected with `COLUMNS` back into an entry
+fn entry_from_row(row: &Row) -> rusqlite::Result<MemoryEntry> {
+    let tags: String = row.get(1)?;
+    let category: Option<String> = row.get(5)?;
+    let expires_at: Option<i64> = row.get(7)?;
+    let updated_at: Option<i64> = row.get(8)?;
+
+    Ok(MemoryEntry {
+        timestamp: format_timestamp(row.get(2)?),
+        id: row.get(6)?,
+        source: row.get(3)?,
+        importance: row.get(4)?,
+        category: category.and_then(|name| Category::from_name(&name)),
+        // Tags can't contain commas, so they are kept as a single list like "work, rust"
+        tags: tags
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect(),
+        updated: updated_at.map(format_timestamp),
+        expires: expires_at.map(format_timestamp),
+        content: row.get(0)?,
+    })
+}
+
+// Helper function to find the live row the selector points at, along with its row id, or how
+// many live rows there are when it matches none
+fn find_row(
+    transaction: &Transaction,
+    selector: &Selector,
+    now: i64,
+) -> anyhow::Result<Result<(MemoryEntry, i64), usize>> {
+    let with_id = |row: &Row| Ok((entry_from_row(row)?, row.get::<_, i64>(9)?));
+    let found = match selector {
+        Selector::Index(index) => match index.checked_sub(1) {
+            Some(offset) => transaction
+                .query_row(
+                    &format!(
+                        "SELECT {}, id FROM memories WHERE {} ORDER BY id LIMIT 1 OFFSET ?2",
+                        COLUMNS, LIVE
+                    ),
+                    [now, i64::try_from(offset)?],
+                    with_id,
+                )
+                .optional()?,
+            None => None,
+        },
+        Selector::Id(uid) => transaction
+            .query_row(
+                &format!(
+                    "SELECT {}, id FROM memories WHERE {} AND uid = ?2",
+                    COLUMNS, LIVE
+                ),
+                rusqlite::params![now, uid],
+                with_id,
+            )
+            .optional()?,
+    };
+    if let Some(found) = found {
+        return Ok(Ok(found));
+    }
+
+    let count: usize = transaction.query_row(
+        &format!("SELECT COUNT(*) FROM memories WHERE {}", LIVE),
+        [now],
+        |row| row.get(0),
+    )?;
+    Ok(Err(count))
+}
+
+impl MemoryBackend for SqliteBackend {
+    fn add(&self, entries: &[MemoryEntry]) -> anyhow::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+
+        // All of them or none, like the single append to the markdown file
+        let transaction = connection.transaction()?;
+        insert_entries(&transaction, entries)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<MemoryEntry>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(&format!(
+            "SELECT {} FROM memories WHERE {} ORDER BY id",
+            COLUMNS, LIVE
+        ))?;
+        let entries = statement
+            .query_map([unix_now()?], entry_from_row)?
+            .collect::<Result<_, _>>()?;
+
+        Ok(entries)
+    }
+
+    fn delete(&self, selector: &Selector) -> anyhow::Result<Deletion> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+
+        let (entry, id) = match find_row(&transaction, selector, unix_now()?)? {
+            Ok(found) => found,
+            Err(count) => return Ok(Deletion::NotFound { count }),
+        };
+
+        transaction.execute("DELETE FROM memories WHERE id = ?1", [id])?;
+        transaction.commit()?;
+
+        Ok(Deletion::Deleted(entry))
+    }
+
+    fn update(&self, selector: &Selector, content: &str) -> anyhow::Result<Update> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+
+        let now = unix_now()?;
+        let (before, id) = match find_row(&transaction, selector, now)? {
+            Ok(found) => found,
+            Err(count) => return Ok(Update::NotFound { count }),
+        };
+
+        transaction.execute(
+            "UPDATE memories SET content = ?1, updated_at = ?2 WHERE id = ?3",
+            rusqlite::params![content, now, id],
+        )?;
+        transaction.commit()?;
+
+        let after = MemoryEntry {
+            content: content.to_string(),
+            updated: Some(format_timestamp(now)),
+            ..before.clone()
+        };
+        Ok(Update::Updated {
+            before: Box::new(before),
+            after: Box::new(after),
+        })
+    }
+
+    fn clear(&self) -> anyhow::Result<usize> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        // Expired rows go too, but weren't there as far as the caller knows
+        let count: usize = transaction.query_row(
+            &format!("SELECT COUNT(*) FROM memories WHERE {}", LIVE),
+            [unix_now()?],
+            |row| row.get(0),
+        )?;
+        transaction.execute("DELETE FROM memories", [])?;
+        transaction.commit()?;
+
+        Ok(count)
+    }
+
+    fn replace(&self, entries: &[MemoryEntry]) -> anyhow::Result<usize> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let count: usize = transaction.query_row(
+            &format!("SELECT COUNT(*) FROM memories WHERE {}", LIVE),
+            [unix_now()?],
+            |row| row.get(0),
+        )?;
+        transaction.execute("DELETE FROM memories", [])?;
+        insert_entries(&transaction, entries)?;
+        transaction.commit()?;
+
+        Ok(count)
+    }
+
+    fn purge_expired(&self, now: i64) -> anyhow::Result<usize> {
+        let connection = self.connection.lock().unwrap();
+        Ok(connection.execute("DELETE FROM memories WHERE expires_at <= ?1", [now])?)
+    }
+}
+
+// A JSON Lines file, one memory per line such as
+// {"timestamp":"2024-01-15T14:30:00Z","source":"claude","content":"Likes tea"}
+// Fields without a value are left out, like the metadata lines of the markdown file.
+#[derive(Clone, Debug)]
+pub struct JsonLinesBackend {
+    path: PathBuf,
+}
+
+impl JsonLinesBackend {
+    // Use the file at `path`, it is created when the first memory is added
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        JsonLinesBackend { path: path.into() }
+    }
+
+    // Helper function to read every line, the caller holds the lock
+    fn read(&self) -> anyhow::Result<Vec<MemoryEntry>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let text = std::fs::read_to_string(&self.path)?;
+        let (complete, partial) = split_partial_line(&text);
+        let mut entries: Vec<MemoryEntry> = complete
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                rmcp::serde_json::from_str(line).map_err(|e| {
+                    anyhow::anyhow!("{}: line {}: {}", self.path.display(), number + 1, e)
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        // A last line without its newline is kept when it parses, otherwise it is what a
+        // crashed write left behind and is skipped
+        entries.extend(rmcp::serde_json::from_str(partial).ok());
+
+        Ok(entries)
+    }
+
+    // Helper function to replace the whole file with the given entries
+    fn write(&self, entries: &[MemoryEntry]) -> anyhow::Result<()> {
+        replace_file(&self.path, &to_json_lines(entries)?)
+    }
+}
+
+// Helper function to split off the last line when it has no newline, as left by a crashed write
+fn split_partial_line(text: &str) -> (&str, &str) {
+    let end = text.rfind('\n').map_or(0, |i| i + 1);
+    text.split_at(end)
+}
+
+// Helper function to serialize entries as JSON Lines, newlines in content are escaped
+fn to_json_lines(entries: &[MemoryEntry]) -> anyhow::Result<String> {
+    let mut lines = String::new();
+    for entry in entries {
+        lines.push_str(&rmcp::serde_json::to_string(entry)?);
+        lines.push('\n');
+    }
+    Ok(lines)
+}
+
+impl MemoryBackend for JsonLinesBackend {
+    fn add(&self, entries: &[MemoryEntry]) -> anyhow::Result<()> {
+        // Serialize everything first so the file is written in one go
+        let lines = to_json_lines(entries)?;
+
+        let _lock = FileLock::exclusive(&self.path)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+
+        // New lines start on a line of their own, even after a crashed write. Only then is
+        // the whole file read, to drop what is left of the broken line or end a good one.
+        let mut last = [b'\n'];
+        if file.metadata()?.len() > 0 {
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+        }
+        if last != [b'\n'] {
+            let mut text = String::new();
+            file.seek(SeekFrom::Start(0))?;
+            file.read_to_string(&mut text)?;
+            let (complete, partial) = split_partial_line(&text);
+            if rmcp::serde_json::from_str::<MemoryEntry>(partial).is_ok() {
+                file.write_all(b"\n")?;
+            } else {
+                file.set_len(complete.len() as u64)?;
+            }
+        }
+        file.write_all(lines.as_bytes())?;
+
+        Ok(())
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<MemoryEntry>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let _lock = FileLock::shared(&self.path)?;
+        let mut entries = self.read()?;
+        let now = unix_now()?;
+        entries.retain(|entry| !entry.is_expired(now));
+        Ok(entries)
+    }
+
+    fn delete(&self, selector: &Selector) -> anyhow::Result<Deletion> {
+        if !self.path.exists() {
+            return Ok(Deletion::NotFound { count: 0 });
+        }
+
+        let _lock = FileLock::exclusive(&self.path)?;
+        let mut entries = self.read()?;
+        let (position, live) = selector.live_position(&entries, unix_now()?);
+        let Some(i) = position else {
+            return Ok(Deletion::NotFound { count: live });
+        };
+        let deleted = entries.remove(i);
+        self.write(&entries)?;
+
+        Ok(Deletion::Deleted(deleted))
+    }
+
+    fn update(&self, selector: &Selector, content: &str) -> anyhow::Result<Update> {
+        if !self.path.exists() {
+            return Ok(Update::NotFound { count: 0 });
+        }
+
+        let _lock = FileLock::exclusive(&self.path)?;
+        let mut entries = self.read()?;
+        let now = unix_now()?;
+        let (position, live) = selector.live_position(&entries, now);
+        let Some(i) = position else {
+            return Ok(Update::NotFound { count: live });
+        };
+        let before = entries[i].clone();
+        entries[i].content = content.to_string();
+        entries[i].updated = Some(format_timestamp(now));
+        let after = entries[i].clone();
+        self.write(&entries)?;
+
+        Ok(Update::Updated {
+            before: Box::new(before),
+            after: Box::new(after),
+        })
+    }
+
+    fn clear(&self) -> anyhow::Result<usize> {
+        if !self.path.exists() {
+            return Ok(0);
+        }
+
+        let _lock = FileLock::exclusive(&self.path)?;
+        let now = unix_now()?;
+        let count = self
+            .read()?
+            .iter()
+            .filter(|entry| !entry.is_expired(now))
+            .count();
+        self.write(&[])?;
+
+        Ok(count)
+    }
+
+    fn replace(&self, entries: &[MemoryEntry]) -> anyhow::Result<usize> {
+        // Serialize everything first so a bad entry leaves the file alone
+        let lines = to_json_lines(entries)?;
+
+        let _lock = FileLock::exclusive(&self.path)?;
+        let now = unix_now()?;
+        let count = self
+            .read()?
+            .iter()
+            .filter(|entry| !entry.is_expired(now))
+            .count();
+        replace_file(&self.path, &lines)?;
+
+        Ok(count)
+    }
+
+    fn purge_expired(&self, now: i64) -> anyhow::Result<usize> {
+        if !self.path.exists() {
+            return Ok(0);
+        }
+
+        let _lock = FileLock::exclusive(&self.path)?;
+        let mut entries = self.read()?;
+        let count = entries.len();
+        entries.retain(|entry| !entry.is_expired(now));
+        let purged = count - entries.len();
+        if purged > 0 {
+            self.write(&entries)?;
+        }
+
+        Ok(purged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Helper to create a unique test file for each test, e.g. test_backend_sqlite.db
+    fn get_test_file(test_name: &str, extension: &str) -> String {
+        format!("test_backend_{}.{}", test_name, extension)
+    }
+
+    // Helper to remove a test file, along with the lock file left next to it
+    fn remove_test_file(test_file: &str) {
+        let _ = fs::remove_file(test_file);
+        let _ = fs::remove_file(format!("{}.lock", test_file));
+    }
+
+    // A few entries using every field, as add_memory would save them
+    fn sample_entries() -> Vec<MemoryEntry> {
+        let mut tea = MemoryEntry::new("Likes green tea", Some("zed")).unwrap();
+        tea.importance = Some(4);
+        tea.category = Some(Category::Preference);
+        tea.tags = vec!["food".to_string(), "morning".to_string()];
+        let rust = MemoryEntry::new("Writes Rust at work\non weekdays", None).unwrap();
+        let rome = MemoryEntry::new("Lives in Rome", Some("claude")).unwrap();
+        vec![tea, rust, rome]
+    }
+
+    #[test]
+    fn test_sqlite_backend() {
+        let test_db = get_test_file("sqlite", "db");
+
+        // Clean up
+        let _ = fs::remove_file(&test_db);
+
+        let backend = SqliteBackend::open(&test_db).unwrap();
+        assert!(backend.list().unwrap().is_empty());
+        assert_eq!(
+            backend.get_memories(Page::ALL).unwrap(),
+            "No memories found yet."
+        );
+
+        // Entries come back as they were saved, also after reopening the database
+        let entries = sample_entries();
+        backend.add(&entries[..2]).unwrap();
+        backend.add(&entries[2..]).unwrap();
+        drop(backend);
+        let backend = SqliteBackend::open(&test_db).unwrap();
+        assert_eq!(backend.list().unwrap(), entries);
+
+        assert!(
+            backend
+                .search("TEA", None)
+                .unwrap()
+                .contains("Likes green tea")
+        );
+        assert_eq!(
+            backend.search("coffee", None).unwrap(),
+            "No memories matched your query."
+        );
+
+        // Indexes follow the numbering of get_memories
+        assert!(backend.get_memories(Page::ALL).unwrap().contains("## 2. "));
+        assert_eq!(
+            backend.delete(&Selector::Index(2)).unwrap(),
+            Deletion::Deleted(entries[1].clone())
+        );
+        for index in [0, 3] {
+            assert_eq!(
+                backend.delete(&Selector::Index(index)).unwrap(),
+                Deletion::NotFound { count: 2 }
+            );
+        }
+        assert_eq!(
+            backend.list().unwrap(),
+            [entries[0].clone(), entries[2].clone()]
+        );
+
+        assert_eq!(backend.clear().unwrap(), 2);
+        assert!(backend.list().unwrap().is_empty());
+
+        // Databases from before ids and updates existed get the columns, their rows stay
+        // without an id and can be updated
+        drop(backend);
+        let _ = fs::remove_file(&test_db);
+        Connection::open(&test_db)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE memories (id INTEGER PRIMARY KEY, content TEXT NOT NULL,
+                tags TEXT NOT NULL DEFAULT '', created_at INTEGER NOT NULL, source TEXT,
+                importance INTEGER, category TEXT);
+                INSERT INTO memories (content, created_at) VALUES ('Likes tea', 0);",
+            )
+            .unwrap();
+        let backend = SqliteBackend::open(&test_db).unwrap();
+        assert_eq!(backend.list().unwrap()[0].id, None);
+        backend.add(&entries[..1]).unwrap();
+        assert_eq!(backend.list().unwrap()[1], entries[0]);
+        backend
+            .update(&Selector::Index(1), "Likes tea a lot")
+            .unwrap();
+        drop(backend);
+        let backend = SqliteBackend::open(&test_db).unwrap();
+        let updated = &backend.list().unwrap()[0];
+        assert_eq!(updated.content, "Likes tea a lot");
+        assert!(updated.updated.is_some());
+
+        // Clean up
+        drop(backend);
+        let _ = fs::remove_file(&test_db);
+    }
+
+    #[test]
+    fn test_json_lines_backend() {
+        let test_file = get_test_file("json_lines", "jsonl");
+
+        // Clean up
+        remove_test_file(&test_file);
+
+        let backend = JsonLinesBackend::new(&test_file);
+        assert!(backend.list().unwrap().is_empty());
+        assert_eq!(backend.clear().unwrap(), 0);
+
+        // One object per line, even for content spanning several lines
+        let entries = sample_entries();
+        backend.add(&entries).unwrap();
+        let text = fs::read_to_string(&test_file).unwrap();
+        assert_eq!(text.lines().count(), 3);
+        let second: rmcp::serde_json::Value =
+            rmcp::serde_json::from_str(text.lines().nth(1).unwrap()).unwrap();
+        assert_eq!(
+            second,
+            rmcp::serde_json::json!({
+                "timestamp": entries[1].timestamp,
+                "id": entries[1].id,
+                "content": "Writes Rust at work\non weekdays",
+            })
+        );
+        assert_eq!(backend.list().unwrap(), entries);
+
+        assert_eq!(
+            backend.delete(&Selector::Index(1)).unwrap(),
+            Deletion::Deleted(entries[0].clone())
+        );
+        assert_eq!(
+            backend.delete(&Selector::Index(3)).unwrap(),
+            Deletion::NotFound { count: 2 }
+        );
+        assert_eq!(backend.list().unwrap(), entries[1..]);
+
+        // A line that isn't a memory is reported rather than dropped
+        fs::write(&test_file, format!("{}not json\n", text)).unwrap();
+        let error = backend.list().unwrap_err().to_string();
+        assert!(error.starts_with(&format!("{}: line 4: ", test_file)));
+
+        // A write cut short leaves the last line without its newline: it is skipped, and the
+        // next memory goes where it was
+        fs::write(&test_file, format!("{}{{\"timestamp\":\"2024-01-", text)).unwrap();
+        assert_eq!(backend.list().unwrap(), entries);
+        backend.add(&entries[..1]).unwrap();
+        let mut expected = entries.clone();
+        expected.push(entries[0].clone());
+        assert_eq!(backend.list().unwrap(), expected);
+        assert_eq!(fs::read_to_string(&test_file).unwrap().lines().count(), 4);
+
+        // A last line that is complete but for its newline is kept
+        fs::write(&test_file, text.trim_end()).unwrap();
+        backend.add(&entries[..1]).unwrap();
+        assert_eq!(backend.list().unwrap(), expected);
+
+        fs::write(&test_file, text).unwrap();
+        assert_eq!(backend.clear().unwrap(), 3);
+        assert!(backend.list().unwrap().is_empty());
+
+        // Clean up
+        remove_test_file(&test_file);
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        let test_files = [
+            get_test_file("agree", "md"),
+            get_test_file("agree", "db"),
+            get_test_file("agree", "jsonl"),
+        ];
+
+        // Clean up
+        for test_file in &test_files {
+            remove_test_file(test_file);
+        }
+
+        let backends: [Box<dyn MemoryBackend>; 3] = [
+            Box::new(MarkdownBackend::new(Some(&test_files[0]))),
+            Box::new(SqliteBackend::open(&test_files[1]).unwrap()),
+            Box::new(JsonLinesBackend::new(&test_files[2])),
+        ];
+        let entries = sample_entries();
+        for backend in &backends {
+            backend.add(&entries).unwrap();
+            backend.delete(&Selector::Index(1)).unwrap();
+            // Ids still point at the same memories after the deletion
+            let selector = Selector::Id(entries[1].id.clone().unwrap());
+            assert_eq!(
+                backend.delete(&selector).unwrap(),
+                Deletion::Deleted(entries[1].clone())
+            );
+            backend.add(&entries[1..2]).unwrap();
+
+            // Expired memories are left out until purged, permanent ones stay
+            let mut expiring = MemoryEntry::new("Parked on level 2", None).unwrap();
+            expiring.expire_after(60, 0);
+            backend.add(&[expiring]).unwrap();
+            assert_eq!(backend.count().unwrap(), 2);
+            assert_eq!(backend.purge_expired(unix_now().unwrap()).unwrap(), 1);
+            assert_eq!(backend.purge_expired(i64::MAX).unwrap(), 0);
+        }
+
+        let page = Page {
+            offset: 1,
+            limit: 1,
+        };
+        for backend in &backends[1..] {
+            assert_eq!(
+                backend.get_memories(page).unwrap(),
+                backends[0].get_memories(page).unwrap()
+            );
+            assert_eq!(
+                backend.search("rome", None).unwrap(),
+                backends[0].search("rome", None).unwrap()
+            );
+            assert_eq!(backend.clear().unwrap(), 2);
+        }
+
+        // Clean up
+        drop(backends);
+        for test_file in &test_files {
+            remove_test_file(test_file);
+        }
+    }
+
+    #[test]
+    fn test_backends_update() {
+        let test_files = [
+            get_test_file("update", "md"),
+            get_test_file("update", "db"),
+            get_test_file("update", "jsonl"),
+        ];
+
+        // Clean up
+        for test_file in &test_files {
+            remove_test_file(test_file);
+        }
+
+        let backends: [Box<dyn MemoryBackend>; 3] = [
+            Box::new(MarkdownBackend::new(Some(&test_files[0]))),
+            Box::new(SqliteBackend::open(&test_files[1]).unwrap()),
+            Box::new(JsonLinesBackend::new(&test_files[2])),
+        ];
+        let entries = sample_entries();
+        for backend in &backends {
+            assert_eq!(
+                backend.update(&Selector::Index(1), "Anything").unwrap(),
+                Update::NotFound { count: 0 }
+            );
+            backend.add(&entries).unwrap();
+
+            // The memory keeps its place and metadata, noting when it changed
+            let selector = Selector::Id(entries[1].id.clone().unwrap());
+            let Update::Updated { before, after } =
+                backend.update(&selector, "Writes Rust at home").unwrap()
+            else {
+                panic!("Should update the memory");
+            };
+            assert_eq!(*before, entries[1]);
+            assert_eq!(
+                *after,
+                MemoryEntry {
+                    content: "Writes Rust at home".to_string(),
+                    updated: after.updated.clone(),
+                    ..entries[1].clone()
+                }
+            );
+            assert!(after.updated.is_some());
+            assert_eq!(
+                backend.list().unwrap(),
+                [entries[0].clone(), *after, entries[2].clone()]
+            );
+
+            assert_eq!(
+                backend.update(&Selector::Index(4), "Anything").unwrap(),
+                Update::NotFound { count: 3 }
+            );
+        }
+
+        // Clean up
+        drop(backends);
+        for test_file in &test_files {
+            remove_test_file(test_file);
+        }
+    }
+}
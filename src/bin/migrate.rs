@@ -0,0 +1,36 @@
+// Rewrites a memory file saved with the legacy "2024-01-15 14:30 UTC" timestamps to RFC 3339,
+// e.g. "2024-01-15T14:30:00Z". The server still reads the old ones, but only writes the new.
+use clap::Parser;
+use easy_memory_mcp::memory::migrate_timestamps_in_file;
+use easy_memory_mcp::reminders::reminders_file_name;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(version, about = "Rewrite the timestamps of a memory file as RFC 3339")]
+struct Args {
+    #[arg(
+        env = "MEMORY_MCP_FILE",
+        default_value = "memories.md",
+        help = "Markdown file to migrate, its reminders file is migrated too when there is one"
+    )]
+    memory_file: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    if !args.memory_file.exists() {
+        anyhow::bail!("{} not found", args.memory_file.display());
+    }
+    let reminders_file = PathBuf::from(reminders_file_name(&args.memory_file.to_string_lossy()));
+
+    for path in [args.memory_file, reminders_file] {
+        if !path.exists() {
+            continue;
+        }
+        let count = migrate_timestamps_in_file(&path)?;
+        println!("{}: {} timestamps rewritten", path.display(), count);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,263 @@
+// The change log: an append-only record of every memory added, updated or deleted, to audit
+// how the store got to its current state or to rebuild it.
+//
+// It is a JSON Lines file next to the memory file, one change per line, numbered in the order
+// the changes were made.
+use crate::backend::MemoryBackend;
+use crate::memory::{FileLock, MemoryEntry, unix_now};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// What a change did to a memory
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeOp {
+    Add,
+    Update,
+    Delete,
+}
+
+// A change about to be recorded, see `record_changes`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    pub operation: ChangeOp,
+    pub before: Option<MemoryEntry>,
+    pub after: Option<MemoryEntry>,
+}
+
+impl Change {
+    pub fn add(entry: MemoryEntry) -> Self {
+        Change {
+            operation: ChangeOp::Add,
+            before: None,
+            after: Some(entry),
+        }
+    }
+
+    pub fn update(before: MemoryEntry, after: MemoryEntry) -> Self {
+        Change {
+            operation: ChangeOp::Update,
+            before: Some(before),
+            after: Some(after),
+        }
+    }
+
+    pub fn delete(entry: MemoryEntry) -> Self {
+        Change {
+            operation: ChangeOp::Delete,
+            before: Some(entry),
+            after: None,
+        }
+    }
+}
+
+// A change as recorded in the log. The memory is identified by its id, or the hash standing in
+// for one, as it was before the change.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ChangeLogEntry {
+    pub sequence: u64,
+    // Unix seconds
+    pub timestamp: i64,
+    pub operation: ChangeOp,
+    pub entry_id: String,
+    pub before: Option<MemoryEntry>,
+    pub after: Option<MemoryEntry>,
+}
+
+// Helper function to name the change log kept next to a memory file, e.g.
+// `.memories.changelog.jsonl` for `memories.md`
+pub fn change_log_file_name(memory_file: &Path) -> PathBuf {
+    let stem = memory_file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "memories".to_string());
+    memory_file.with_file_name(format!(".{}.changelog.jsonl", stem))
+}
+
+// Helper function to read every change in the log, oldest first. A missing log has none.
+fn read_all_changes(log_path: &Path) -> anyhow::Result<Vec<ChangeLogEntry>> {
+    if !log_path.exists() {
+        return Ok(Vec::new());
+    }
+    std::fs::read_to_string(log_path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            rmcp::serde_json::from_str(line)
+                .map_err(|e| anyhow::anyhow!("{} line {}: {}", log_path.display(), i + 1, e))
+        })
+        .collect()
+}
+
+// Helper function to append changes to the log, numbering them after the last one recorded
+pub fn record_changes(log_path: &Path, changes: &[Change]) -> anyhow::Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    // Held from reading the last number to the append, so two servers can't reuse it
+    let _lock = FileLock::exclusive(log_path)?;
+    let last = read_all_changes(log_path)?
+        .last()
+        .map_or(0, |change| change.sequence);
+    let timestamp = unix_now()?;
+
+    let mut lines = String::new();
+    for (sequence, change) in (last + 1..).zip(changes) {
+        let entry_id = change
+            .before
+            .as_ref()
+            .or(change.after.as_ref())
+            .map(MemoryEntry::id_or_hash)
+            .unwrap_or_default();
+        let entry = ChangeLogEntry {
+            sequence,
+            timestamp,
+            operation: change.operation,
+            entry_id,
+            before: change.before.clone(),
+            after: change.after.clone(),
+        };
+        lines.push_str(&rmcp::serde_json::to_string(&entry)?);
+        lines.push('\n');
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)?;
+    file.write_all(lines.as_bytes())?;
+    Ok(())
+}
+
+// Helper function to read the changes recorded after `since_sequence`, at most `limit` of them
+pub fn get_change_log(
+    log_path: &Path,
+    since_sequence: Option<u64>,
+    limit: Option<usize>,
+) -> anyhow::Result<Vec<ChangeLogEntry>> {
+    let _lock = FileLock::shared(log_path)?;
+    let since_sequence = since_sequence.unwrap_or(0);
+    Ok(read_all_changes(log_path)?
+        .into_iter()
+        .filter(|change| change.sequence > since_sequence)
+        .take(limit.unwrap_or(usize::MAX))
+        .collect())
+}
+
+// Helper function to rebuild the store from the log: every change is applied in order, then
+// the result replaces whatever the backend held. Returns how many changes were applied.
+pub fn replay_change_log(log_path: &Path, backend: &dyn MemoryBackend) -> anyhow::Result<usize> {
+    let changes = get_change_log(log_path, None, None)?;
+
+    let mut entries: Vec<MemoryEntry> = Vec::new();
+    for change in &changes {
+        let position = entries
+            .iter()
+            .position(|entry| entry.id_or_hash() == change.entry_id);
+        match (change.operation, position, &change.after) {
+            (ChangeOp::Add, _, Some(after)) => entries.push(after.clone()),
+            (ChangeOp::Update, Some(position), Some(after)) => entries[position] = after.clone(),
+            (ChangeOp::Delete, Some(position), _) => {
+                entries.remove(position);
+            }
+            _ => anyhow::bail!(
+                "change {} can't be applied: no memory {} to {:?}",
+                change.sequence,
+                change.entry_id,
+                change.operation
+            ),
+        }
+    }
+
+    backend.replace(&entries)?;
+    Ok(changes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::JsonLinesBackend;
+    use std::fs;
+
+    // Helper to remove a test file, along with the lock file left next to it
+    fn remove_test_file(test_file: &Path) {
+        let _ = fs::remove_file(test_file);
+        let _ = fs::remove_file(format!("{}.lock", test_file.display()));
+    }
+
+    #[test]
+    fn test_change_log_file_name() {
+        assert_eq!(
+            change_log_file_name(Path::new("/data/memories.md")),
+            Path::new("/data/.memories.changelog.jsonl")
+        );
+        assert_eq!(
+            change_log_file_name(Path::new("notes")),
+            Path::new(".notes.changelog.jsonl")
+        );
+    }
+
+    #[test]
+    fn test_change_log_replay() {
+        let log = PathBuf::from("test_changelog_replay.jsonl");
+        let store = PathBuf::from("test_changelog_replay_store.jsonl");
+        let rebuilt = PathBuf::from("test_changelog_replay_rebuilt.jsonl");
+
+        // Clean up
+        for file in [&log, &store, &rebuilt] {
+            remove_test_file(file);
+        }
+
+        // Add two memories, update one and delete the other, as the server would
+        let backend = JsonLinesBackend::new(&store);
+        let tea = MemoryEntry::new("Likes tea", None).unwrap();
+        let rome = MemoryEntry::new("Lives in Rome", None).unwrap();
+        backend.add(&[tea.clone(), rome.clone()]).unwrap();
+        record_changes(&log, &[Change::add(tea.clone()), Change::add(rome.clone())]).unwrap();
+        let mut green_tea = tea.clone();
+        green_tea.content = "Likes green tea".to_string();
+        backend.replace(&[green_tea.clone(), rome.clone()]).unwrap();
+        record_changes(&log, &[Change::update(tea.clone(), green_tea.clone())]).unwrap();
+        backend
+            .delete(&crate::memory::Selector::Id(rome.id.clone().unwrap()))
+            .unwrap();
+        record_changes(&log, &[Change::delete(rome.clone())]).unwrap();
+
+        // Numbered in order, each naming the memory it changed
+        let changes = get_change_log(&log, None, None).unwrap();
+        let summary: Vec<(u64, ChangeOp, String)> = changes
+            .iter()
+            .map(|change| (change.sequence, change.operation, change.entry_id.clone()))
+            .collect();
+        let tea_id = tea.id.clone().unwrap();
+        let rome_id = rome.id.clone().unwrap();
+        assert_eq!(
+            summary,
+            [
+                (1, ChangeOp::Add, tea_id.clone()),
+                (2, ChangeOp::Add, rome_id.clone()),
+                (3, ChangeOp::Update, tea_id),
+                (4, ChangeOp::Delete, rome_id),
+            ]
+        );
+        assert_eq!(changes[2].before.as_ref(), Some(&tea));
+        assert_eq!(changes[2].after.as_ref(), Some(&green_tea));
+        let later = get_change_log(&log, Some(1), Some(2)).unwrap();
+        assert_eq!(later, changes[1..3]);
+
+        // Replaying on an empty store gets to the same state
+        let replayed = JsonLinesBackend::new(&rebuilt);
+        assert_eq!(replay_change_log(&log, &replayed).unwrap(), 4);
+        assert_eq!(replayed.list().unwrap(), backend.list().unwrap());
+        assert_eq!(replayed.list().unwrap(), [green_tea]);
+
+        // Clean up
+        for file in [&log, &store, &rebuilt] {
+            remove_test_file(file);
+        }
+    }
+}
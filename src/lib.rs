@@ -0,0 +1,5 @@
+// The memory engine behind the easy-memory-mcp server, usable on its own
+pub mod backend;
+pub mod changelog;
+pub mod memory;
+pub mod reminders;
@@ -0,0 +1,347 @@
+// Reminders: notes scheduled for a later time, kept apart from the memories.
+//
+// Reminders live in their own markdown file next to the memory file, one block per reminder
+// headed by its due time.
+// Once a reminder has been handed out it is marked delivered, so it only comes up once.
+use crate::memory::{FileLock, format_timestamp, parse_header, parse_timestamp, replace_file};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+// A single reminder as stored in the reminders file:
+//
+// ## 2024-01-15T14:30:00Z
+// delivered: true
+// Call the dentist
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reminder {
+    pub due: i64,
+    pub delivered: bool,
+    pub content: String,
+}
+
+impl Reminder {
+    fn to_markdown(&self) -> String {
+        let delivered = if self.delivered {
+            format!("{}\n", DELIVERED_LINE)
+        } else {
+            String::new()
+        };
+        // Content starting like the delivered line is escaped, as in the memory file
+        let escape = if self.content.lines().next().is_some_and(needs_escape) {
+            "\\"
+        } else {
+            ""
+        };
+        format!(
+            "## {}\n{}{}{}\n\n",
+            format_timestamp(self.due),
+            delivered,
+            escape,
+            self.content
+        )
+    }
+}
+
+// The line marking a reminder as handed out, right below its header
+const DELIVERED_LINE: &str = "delivered: true";
+
+// Helper function to check whether the first line of a reminder has to be escaped with a
+// backslash: when it could pass for the delivered line, or starts with a backslash itself
+fn needs_escape(line: &str) -> bool {
+    line.starts_with('\\') || line == DELIVERED_LINE
+}
+
+// Helper function to reject reminder content with a line that would start another reminder
+pub fn check_reminder_content(content: &str) -> anyhow::Result<()> {
+    match content.lines().find(|line| parse_header(line).is_some()) {
+        Some(line) => anyhow::bail!(
+            "Reminder content can't have a line like {:?}, it would start another reminder",
+            line
+        ),
+        None => Ok(()),
+    }
+}
+
+// Helper function to name the reminders file kept next to a memory file,
+// e.g. `memories.reminders.md` for `memories.md`
+pub fn reminders_file_name(memory_file: &str) -> String {
+    format!(
+        "{}.reminders.md",
+        memory_file.strip_suffix(".md").unwrap_or(memory_file)
+    )
+}
+
+// Helper function to parse a due time written like the memory timestamps
+pub fn parse_due(due: &str) -> anyhow::Result<i64> {
+    parse_timestamp(due).ok_or_else(|| {
+        anyhow::anyhow!(
+            "due must be RFC 3339 like \"2024-01-15T14:30:00Z\", got \"{}\"",
+            due
+        )
+    })
+}
+
+// Helper function to split the reminders file into its reminders
+fn parse_reminders(text: &str) -> Vec<Reminder> {
+    let mut reminders = Vec::new();
+    let mut current: Option<(Reminder, Vec<&str>)> = None;
+
+    for line in text.lines() {
+        if let Some(due) = parse_header(line).and_then(parse_timestamp) {
+            reminders.extend(current.take().map(finish_reminder));
+            let reminder = Reminder {
+                due,
+                delivered: false,
+                content: String::new(),
+            };
+            current = Some((reminder, Vec::new()));
+        } else if let Some((reminder, body)) = current.as_mut() {
+            if body.is_empty() && !reminder.delivered && line == DELIVERED_LINE {
+                reminder.delivered = true;
+            } else {
+                body.push(line);
+            }
+        }
+    }
+    reminders.extend(current.map(finish_reminder));
+
+    reminders
+}
+
+fn finish_reminder((mut reminder, mut body): (Reminder, Vec<&str>)) -> Reminder {
+    if let Some(first) = body.first_mut() {
+        *first = first.strip_prefix('\\').unwrap_or(first);
+    }
+    reminder.content = body.join("\n").trim_end().to_string();
+    reminder
+}
+
+// Helper function to schedule a reminder, appending it to the reminders file
+pub fn remind_me_in_file(
+    content: &str,
+    due: i64,
+    file_path: Option<&str>,
+) -> anyhow::Result<String> {
+    // Get the reminders file path
+    let filename = file_path.unwrap_or("reminders.md");
+    let mut path = PathBuf::from(".");
+    path.push(filename);
+
+    check_reminder_content(content)?;
+    let reminder = Reminder {
+        due,
+        delivered: false,
+        content: content.to_string(),
+    };
+
+    // Create or append to the file, never in the middle of handing reminders out
+    let _lock = FileLock::exclusive(&path)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    file.write_all(reminder.to_markdown().as_bytes())?;
+
+    Ok(format!("Reminder set for {}.", format_timestamp(due)))
+}
+
+// Helper function to hand out the reminders due at `now` that weren't delivered yet,
+// marking them delivered in the file
+pub fn due_reminders_from_file(now: i64, file_path: Option<&str>) -> anyhow::Result<String> {
+    use std::fs;
+
+    // Get the reminders file path
+    let filename = file_path.unwrap_or("reminders.md");
+    let mut path = PathBuf::from(".");
+    path.push(filename);
+
+    // Held from the read to the rename, so a reminder set in between isn't lost
+    let _lock = FileLock::exclusive(&path)?;
+    if !path.exists() {
+        return Ok("No reminders are due.".to_string());
+    }
+
+    let mut reminders = parse_reminders(&fs::read_to_string(&path)?);
+    let mut due = Vec::new();
+    for reminder in reminders.iter_mut() {
+        if !reminder.delivered && reminder.due <= now {
+            reminder.delivered = true;
+            due.push(reminder.clone());
+        }
+    }
+
+    if due.is_empty() {
+        return Ok("No reminders are due.".to_string());
+    }
+
+    // Written next to the file and renamed over it, so no reminder is lost halfway
+    let markdown: String = reminders.iter().map(Reminder::to_markdown).collect();
+    replace_file(&path, &markdown)?;
+
+    let mut report = "Due reminders:\n".to_string();
+    for reminder in &due {
+        report.push_str(&format!(
+            "- {}: {}\n",
+            format_timestamp(reminder.due),
+            reminder.content
+        ));
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Helper to create a unique test file for each test
+    fn get_test_file(test_name: &str) -> String {
+        format!("test_reminders_{}.md", test_name)
+    }
+
+    // Helper to remove a test file, along with the lock file left next to it
+    fn remove_test_file(test_file: &str) {
+        let _ = fs::remove_file(test_file);
+        let _ = fs::remove_file(format!("{}.lock", test_file));
+    }
+
+    #[test]
+    fn test_reminder_is_delivered_once_when_due() {
+        let test_file = get_test_file("delivered_once");
+
+        // Clean up
+        remove_test_file(&test_file);
+
+        let due = parse_due("2024-01-15T14:30:00Z").unwrap();
+        let message = remind_me_in_file("Call the dentist", due, Some(&test_file)).unwrap();
+        assert_eq!(message, "Reminder set for 2024-01-15T14:30:00Z.");
+        remind_me_in_file("Renew passport", due + 86400, Some(&test_file)).unwrap();
+
+        // Not yet due
+        assert_eq!(
+            due_reminders_from_file(due - 60, Some(&test_file)).unwrap(),
+            "No reminders are due."
+        );
+
+        // Due, and only the first one
+        assert_eq!(
+            due_reminders_from_file(due + 60, Some(&test_file)).unwrap(),
+            "Due reminders:\n- 2024-01-15T14:30:00Z: Call the dentist\n"
+        );
+
+        // Not repeated, while the later one still comes up once it is due
+        assert_eq!(
+            due_reminders_from_file(due + 120, Some(&test_file)).unwrap(),
+            "No reminders are due."
+        );
+        assert_eq!(
+            due_reminders_from_file(due + 86400, Some(&test_file)).unwrap(),
+            "Due reminders:\n- 2024-01-16T14:30:00Z: Renew passport\n"
+        );
+
+        let reminders = parse_reminders(&fs::read_to_string(&test_file).unwrap());
+        assert!(reminders.iter().all(|reminder| reminder.delivered));
+
+        // Clean up
+        remove_test_file(&test_file);
+    }
+
+    #[test]
+    fn test_reminders_set_while_delivering_are_kept() {
+        let test_file = get_test_file("concurrent");
+
+        // Clean up
+        remove_test_file(&test_file);
+
+        // Reminders are set while due ones are handed out, none of them may be lost
+        let due = parse_due("2024-01-15T14:30:00Z").unwrap();
+        let setter = {
+            let test_file = test_file.clone();
+            std::thread::spawn(move || {
+                for i in 0..50 {
+                    remind_me_in_file(&format!("Reminder {}", i), due, Some(&test_file)).unwrap();
+                }
+            })
+        };
+        let mut delivered = 0;
+        while !setter.is_finished() {
+            let report = due_reminders_from_file(due, Some(&test_file)).unwrap();
+            delivered += report.matches("Reminder ").count();
+        }
+        setter.join().unwrap();
+        let report = due_reminders_from_file(due, Some(&test_file)).unwrap();
+        delivered += report.matches("Reminder ").count();
+
+        assert_eq!(delivered, 50);
+        assert_eq!(
+            parse_reminders(&fs::read_to_string(&test_file).unwrap()).len(),
+            50
+        );
+
+        // Clean up
+        remove_test_file(&test_file);
+    }
+
+    #[test]
+    fn test_reminder_content_is_escaped() {
+        let test_file = get_test_file("escaped");
+
+        // Clean up
+        remove_test_file(&test_file);
+
+        // Content looking like the delivered line still fires, and reads back as written
+        let due = parse_due("2024-01-15T14:30:00Z").unwrap();
+        for content in [
+            "delivered: true",
+            "\\d is a regex",
+            "delivered: true\nreally",
+        ] {
+            remind_me_in_file(content, due, Some(&test_file)).unwrap();
+        }
+        let reminders = parse_reminders(&fs::read_to_string(&test_file).unwrap());
+        let contents: Vec<&str> = reminders.iter().map(|r| r.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                "delivered: true",
+                "\\d is a regex",
+                "delivered: true\nreally"
+            ]
+        );
+        assert!(reminders.iter().all(|reminder| !reminder.delivered));
+
+        // Once delivered it keeps its content
+        due_reminders_from_file(due, Some(&test_file)).unwrap();
+        let reminders = parse_reminders(&fs::read_to_string(&test_file).unwrap());
+        assert!(reminders.iter().all(|reminder| reminder.delivered));
+        assert_eq!(reminders[0].content, "delivered: true");
+
+        // A header in the content would split it in two
+        let error = remind_me_in_file(
+            "Call the dentist\n## 2024-01-15T14:30:00Z\nand the vet",
+            due,
+            Some(&test_file),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Reminder content can't have a line like \"## 2024-01-15T14:30:00Z\", it would start another reminder"
+        );
+        assert_eq!(
+            parse_reminders(&fs::read_to_string(&test_file).unwrap()).len(),
+            3
+        );
+
+        // Clean up
+        remove_test_file(&test_file);
+    }
+
+    #[test]
+    fn test_parse_due_rejects_malformed_times() {
+        assert!(parse_due("2024-01-15T14:30:00Z").is_ok());
+        assert!(parse_due("2024-01-15 14:30 UTC").is_ok());
+        assert_eq!(
+            parse_due("tomorrow").unwrap_err().to_string(),
+            "due must be RFC 3339 like \"2024-01-15T14:30:00Z\", got \"tomorrow\""
+        );
+    }
+}
@@ -0,0 +1,56 @@
+// The migrate binary, run on a copy of a memory file saved before RFC 3339 timestamps
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+// Helper to run the migrate binary on a memory file, returning what it printed
+fn migrate(memory_file: &PathBuf) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_migrate"))
+        .arg(memory_file)
+        .output()
+        .expect("Should run migrate");
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_migrate_legacy_timestamps() {
+    let dir = std::env::temp_dir().join(format!("easy-memory-mcp-migrate-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let memory_file = dir.join("memories.md");
+    let reminders_file = dir.join("memories.reminders.md");
+    fs::copy("tests/fixtures/legacy_memories.md", &memory_file).unwrap();
+    fs::write(
+        &reminders_file,
+        "## 2024-01-20 08:00 UTC\nCall the dentist\n\n",
+    )
+    .unwrap();
+
+    // Headers, their timestamps and markers are rewritten, content is left alone
+    assert_eq!(
+        migrate(&memory_file),
+        format!(
+            "{}: 6 timestamps rewritten\n{}: 1 timestamps rewritten\n",
+            memory_file.display(),
+            reminders_file.display()
+        )
+    );
+    assert_eq!(
+        fs::read_to_string(&memory_file).unwrap(),
+        fs::read_to_string("tests/fixtures/migrated_memories.md").unwrap()
+    );
+    assert_eq!(
+        fs::read_to_string(&reminders_file).unwrap(),
+        "## 2024-01-20T08:00:00Z\nCall the dentist\n\n"
+    );
+
+    // Running it again finds nothing left to do
+    assert!(migrate(&memory_file).contains(": 0 timestamps rewritten\n"));
+    assert_eq!(
+        fs::read_to_string(&memory_file).unwrap(),
+        fs::read_to_string("tests/fixtures/migrated_memories.md").unwrap()
+    );
+
+    // Clean up
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,96 @@
+// The server run with --transport http, browsed through its web view of the memories
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+// Kills the server when the test ends, passing or not
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// Helper to send a GET request, returning the status line and the body
+fn get(port: u16, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).expect("Should connect");
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").expect("Should have a body");
+    (head.lines().next().unwrap().to_string(), body.to_string())
+}
+
+#[test]
+fn test_permalinks() {
+    let dir =
+        std::env::temp_dir().join(format!("easy-memory-mcp-permalinks-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let memory_file = dir.join("memories.md");
+    fs::write(
+        &memory_file,
+        "## 2024-01-15T14:30:00Z\nid: tea00001\nLikes tea\n\n\
+         ## 2024-01-16T09:05:00Z\nid: rome0001\nLives in Rome\nnear the river\n\n",
+    )
+    .unwrap();
+
+    // Any free port will do
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let _server = Server(
+        Command::new(env!("CARGO_BIN_EXE_easy-memory-mcp"))
+            .args(["--transport", "http", "--port", &port.to_string()])
+            .arg("--memory-file")
+            .arg(&memory_file)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Should start the server"),
+    );
+    let started = Instant::now();
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "Server did not start"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    // The index links every memory by id
+    let (status, index) = get(port, "/");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(index.contains("<a href=\"/tea00001\">Likes tea</a>"));
+    assert!(index.contains("<a href=\"/rome0001\">Lives in Rome</a>"));
+
+    // Following a permalink shows that memory alone
+    let href = index
+        .split("href=\"")
+        .nth(2)
+        .unwrap()
+        .split('"')
+        .next()
+        .unwrap();
+    assert_eq!(href, "/rome0001");
+    let (status, page) = get(port, href);
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(page.contains("Lives in Rome\nnear the river</pre>"));
+    assert!(!page.contains("Likes tea"));
+
+    let (status, _) = get(port, "/nosuchid");
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+
+    // Clean up
+    let _ = fs::remove_dir_all(&dir);
+}